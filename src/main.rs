@@ -1,9 +1,15 @@
 //! # A tool for translating textures to new UV mappings on similar objects
 
-use anyhow::Result;
-use clap::Parser;
+// The per-channel `for c in 0..3` loops and `Rgb { 0: .. }` literals are the
+// house style throughout this tool; keep clippy from rewriting them.
+#![allow(clippy::needless_range_loop, clippy::init_numbered_fields)]
+
+use anyhow::{bail, ensure, Result};
+use std::collections::HashMap;
+use std::fs;
+use clap::{Parser, ValueEnum};
 use image::{io::Reader, ImageBuffer, Rgb};
-use nalgebra::{Matrix2, Matrix3, Vector2, Vector3};
+use nalgebra::{DMatrix, DVector, Matrix3, Vector2, Vector3, SVD};
 
 type Image = ImageBuffer<Rgb<u8>, Vec<u8>>;
 type Triangle = (Vector2<f64>, Vector2<f64>, Vector2<f64>);
@@ -14,84 +20,962 @@ struct Position {
     y: u32,
 }
 
+/// How to sample the input texture at a fractional source coordinate
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum Interpolation {
+    Nearest,
+    Bilinear,
+}
+
+/// Which rendering backend performs the piecewise warp
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum BackendKind {
+    /// Per-pixel inverse sampling on the CPU (default, always available)
+    Cpu,
+    /// Hardware triangle rasterization via `wgpu`
+    Gpu,
+}
+
+/// Which family of transform to fit to the marker correspondences
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum Mapping {
+    /// Piecewise-affine warp over a Delaunay mesh of the markers (one global
+    /// affine map estimated by RANSAC with `--auto`)
+    Affine,
+    /// A single projective homography fit to all markers at once (to the
+    /// RANSAC inliers with `--auto`)
+    Homography,
+}
+
 /// A tool for translating textures to new UV mappings on similar objects
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     input_uv: String,
     output_uv: String,
-    // map_file: String,
+    output: String,
+    /// Read point correspondences from a text mapping file instead of markers
+    #[arg(long)]
+    map_file: Option<String>,
+    /// How to sample the input texture
+    #[arg(long, value_enum, default_value_t = Interpolation::Bilinear)]
+    interpolation: Interpolation,
+    /// Which transform family to fit. Defaults to affine for three markers and
+    /// homography for four or more; with `--auto` it defaults to homography.
+    #[arg(long, value_enum)]
+    mapping: Option<Mapping>,
+    /// Detect and match features automatically instead of reading color markers
+    #[arg(long)]
+    auto: bool,
+    /// Maximum reprojection error, in pixels, for a RANSAC inlier
+    #[arg(long, default_value_t = 3.0)]
+    ransac_threshold: f64,
+    /// Number of RANSAC sampling iterations
+    #[arg(long, default_value_t = 1000)]
+    ransac_iters: usize,
+    /// Which backend renders the piecewise-affine warp
+    #[arg(long, value_enum, default_value_t = BackendKind::Cpu)]
+    backend: BackendKind,
+    /// Gaussian blur sigma applied after warping
+    #[arg(long)]
+    blur_sigma: Option<f64>,
+    /// Row-major weights of an odd NxN convolution kernel applied after warping
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    convolve: Option<Vec<f64>>,
+    /// Divisor the convolution result is scaled by
+    #[arg(long, default_value_t = 1.0)]
+    convolve_divisor: f64,
+    /// Bias added to each convolved channel
+    #[arg(long, default_value_t = 0.0)]
+    convolve_bias: f64,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
     println!("Loading UVs");
-    let result = Reader::open(args.input_uv)?;
-    let input_image = result.decode()?;
-    let input_image = input_image.to_rgb8();
+    let input_image = Reader::open(args.input_uv)?.decode()?.to_rgb8();
+    let output_image = Reader::open(args.output_uv)?.decode()?.to_rgb8();
+
+    let (width, height) = output_image.dimensions();
+    let mut output_image = if args.auto {
+        // Automatic correspondence: detect and match features, then estimate a
+        // single global transform robustly with RANSAC.
+        println!("Detecting features");
+        let input_features = detect_features(&input_image);
+        let output_features = detect_features(&output_image);
+        println!(
+            "{} / {} features",
+            input_features.len(),
+            output_features.len()
+        );
 
-    println!("Finding Triangles");
-    let positions = find_markers(input_image);
-    println!("{positions:?}");
+        let matches = match_features(&input_features, &output_features);
+        println!("{} tentative matches", matches.len());
 
-    let input_triangle = make_triangle(&positions[0], &positions[1], &positions[2]);
-    let output_triangle = make_triangle(&positions[0], &positions[1], &positions[2]);
+        let input_points: Vec<_> = matches
+            .iter()
+            .map(|&(i, _)| input_features[i].position)
+            .collect();
+        let output_points: Vec<_> = matches
+            .iter()
+            .map(|&(_, j)| output_features[j].position)
+            .collect();
+
+        let mapping = args.mapping.unwrap_or(Mapping::Homography);
+        println!("Estimating transform with RANSAC");
+        let transform = ransac(
+            &input_points,
+            &output_points,
+            mapping,
+            args.ransac_threshold,
+            args.ransac_iters,
+        )?;
+        let inverse = transform.try_inverse().unwrap();
+
+        println!("Warping texture");
+        warp_projective(&input_image, &inverse, width, height, args.interpolation)
+    } else {
+        // Correspondences come from a mapping file when given, otherwise from
+        // the color markers painted on the two images.
+        let (input_points, output_points, file_mode) = if let Some(path) = &args.map_file {
+            println!("Reading mapping file");
+            let correspondences = read_mapping(path)?;
+            println!("{} correspondences", correspondences.input.len());
+            (
+                correspondences.input,
+                correspondences.output,
+                correspondences.mode,
+            )
+        } else {
+            println!("Finding markers");
+            let input_points = to_points(&find_markers(input_image.clone()));
+            let output_points = to_points(&find_markers(output_image.clone()));
+            println!("{} markers", input_points.len());
+            (input_points, output_points, None)
+        };
+
+        // The two lists are matched by index, so they must describe the same
+        // number of correspondences.
+        ensure!(
+            input_points.len() == output_points.len(),
+            "input and output have different numbers of markers ({} vs {})",
+            input_points.len(),
+            output_points.len()
+        );
+
+        // Affine keeps the piecewise mesh; homography fits one projective map to
+        // every marker. An explicit flag wins over the file's `mode` directive,
+        // which in turn wins over the marker-count default.
+        let mapping = args.mapping.or(file_mode).unwrap_or(if input_points.len() >= 4 {
+            Mapping::Homography
+        } else {
+            Mapping::Affine
+        });
+
+        match mapping {
+            Mapping::Affine => {
+                println!("Triangulating");
+                let triangles = triangulate(&input_points);
+                println!("{} triangles", triangles.len());
+
+                println!("Building matrices");
+                let warps = build_warps(&input_points, &output_points, &triangles);
+
+                println!("Warping texture");
+                let backend = make_backend(args.backend);
+                backend.render(&input_image, &warps, width, height, args.interpolation)
+            }
+            Mapping::Homography => {
+                println!("Fitting homography");
+                let homography = fit_homography(&input_points, &output_points);
+                let inverse = homography.try_inverse().unwrap();
 
-    println!("Building Matrices");
-    let matrix = get_transform(input_triangle, output_triangle);
+                println!("Warping texture");
+                warp_projective(&input_image, &inverse, width, height, args.interpolation)
+            }
+        }
+    };
 
-    println!("Transformation matrix: {matrix:?}");
+    // Post-warp resampling filters: smooth aliased minification or sharpen and
+    // hide triangle seams before the image is written out.
+    if let Some(sigma) = args.blur_sigma {
+        println!("Applying Gaussian blur");
+        output_image = gaussian_blur(&output_image, sigma);
+    }
+    if let Some(kernel) = &args.convolve {
+        let size = (kernel.len() as f64).sqrt() as usize;
+        ensure!(
+            size * size == kernel.len() && size % 2 == 1,
+            "--convolve needs the weights of an odd NxN kernel"
+        );
+        println!("Applying convolution");
+        output_image = convolve(&output_image, kernel, args.convolve_divisor, args.convolve_bias);
+    }
 
-    // Save
-    // println!("Saving output image");
-    // let output_image = DynamicImage::from(img).to_rgb8();
-    // output_image.save(args.map_file)?;
+    println!("Saving output image");
+    output_image.save(args.output)?;
 
     Ok(())
 }
 
-/// Returns the position of each point
+/// A single output triangle paired with the inverse of its affine transform.
 ///
-/// The order considers the RGB colors as a weight BGR where R is least significant
-/// and B most significant. Look at get_precendence for the exact definition.
-fn find_markers(image: Image) -> Vec<Position> {
+/// `inverse` maps an output coordinate back to the input texture, so sampling
+/// is destination-driven and the output has no holes.
+struct WarpTriangle {
+    /// The triangle in input-texture space, used as sampling UVs.
+    input: Triangle,
+    output: Triangle,
+    inverse: Matrix3<f64>,
+}
+
+/// Point correspondences loaded from a mapping file, with any transform family
+/// the file requested.
+struct Correspondences {
+    input: Vec<Vector2<f64>>,
+    output: Vec<Vector2<f64>>,
+    mode: Option<Mapping>,
+}
+
+/// Parses a line-oriented point-mapping file.
+///
+/// Each `point <in_x> <in_y> <out_x> <out_y>` line adds one correspondence, and
+/// an optional `mode affine|homography` directive selects the transform family.
+/// Blank lines and `#` comments are ignored.
+fn read_mapping(path: &str) -> Result<Correspondences> {
+    let text = fs::read_to_string(path)?;
+    let mut input = Vec::new();
+    let mut output = Vec::new();
+    let mut mode = None;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("point") => {
+                let values: Vec<f64> = tokens
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| anyhow::anyhow!("line {}: {e}", number + 1))?;
+                ensure!(
+                    values.len() == 4,
+                    "line {}: point needs four coordinates",
+                    number + 1
+                );
+                input.push(Vector2::new(values[0], values[1]));
+                output.push(Vector2::new(values[2], values[3]));
+            }
+            Some("mode") => {
+                mode = Some(match tokens.next() {
+                    Some("affine") => Mapping::Affine,
+                    Some("homography") => Mapping::Homography,
+                    other => bail!("line {}: unknown mode {other:?}", number + 1),
+                });
+            }
+            Some(keyword) => bail!("line {}: unknown directive {keyword:?}", number + 1),
+            None => unreachable!("blank lines are skipped above"),
+        }
+    }
+
+    Ok(Correspondences {
+        input,
+        output,
+        mode,
+    })
+}
+
+/// Converts marker positions into floating-point points.
+fn to_points(positions: &[Position]) -> Vec<Vector2<f64>> {
+    positions
+        .iter()
+        .map(|p| Vector2::new(p.x as f64, p.y as f64))
+        .collect()
+}
+
+/// Builds the per-triangle output shapes and inverse transforms.
+///
+/// The connectivity found on the input markers is reused verbatim on the
+/// output markers — both lists share one ordering (color precedence for
+/// markers, line order for a mapping file), so index `i` refers to the same
+/// logical point in either image.
+fn build_warps(
+    input: &[Vector2<f64>],
+    output: &[Vector2<f64>],
+    triangles: &[[usize; 3]],
+) -> Vec<WarpTriangle> {
+    triangles
+        .iter()
+        .map(|&[a, b, c]| {
+            let input_triangle = (input[a], input[b], input[c]);
+            let output_triangle = (output[a], output[b], output[c]);
+            let matrix = fit_affine(
+                &[input[a], input[b], input[c]],
+                &[output[a], output[b], output[c]],
+            );
+
+            WarpTriangle {
+                input: input_triangle,
+                output: output_triangle,
+                inverse: matrix.try_inverse().unwrap(),
+            }
+        })
+        .collect()
+}
+
+/// Warps `input` by locating the output triangle containing each output pixel
+/// and sampling the input through that triangle's inverse transform.
+fn warp_piecewise(
+    input: &Image,
+    warps: &[WarpTriangle],
+    width: u32,
+    height: u32,
+    interpolation: Interpolation,
+) -> Image {
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let point = Vector2::new(x as f64, y as f64);
+
+        for warp in warps {
+            if in_triangle(&warp.output, point) {
+                let source = warp.inverse * Vector3::new(point.x, point.y, 1.0);
+                return sample(input, source.x, source.y, interpolation);
+            }
+        }
+
+        Rgb { 0: [0; 3] }
+    })
+}
+
+/// A rendering backend that turns the per-triangle warp into an output image.
+///
+/// The CPU path is the default and the one the math tests exercise; the GPU
+/// path produces the same result by rasterizing the output triangles with the
+/// correspondence UVs as vertex attributes.
+trait Backend {
+    fn render(
+        &self,
+        input: &Image,
+        warps: &[WarpTriangle],
+        width: u32,
+        height: u32,
+        interpolation: Interpolation,
+    ) -> Image;
+}
+
+/// Per-pixel inverse sampling on the CPU.
+struct Cpu;
+
+impl Backend for Cpu {
+    fn render(
+        &self,
+        input: &Image,
+        warps: &[WarpTriangle],
+        width: u32,
+        height: u32,
+        interpolation: Interpolation,
+    ) -> Image {
+        warp_piecewise(input, warps, width, height, interpolation)
+    }
+}
+
+/// Hardware triangle rasterization via `wgpu`.
+struct Gpu;
+
+impl Backend for Gpu {
+    fn render(
+        &self,
+        input: &Image,
+        warps: &[WarpTriangle],
+        width: u32,
+        height: u32,
+        interpolation: Interpolation,
+    ) -> Image {
+        pollster::block_on(render_gpu(input, warps, width, height, interpolation))
+    }
+}
+
+/// Selects the backend implementation for the requested kind.
+fn make_backend(kind: BackendKind) -> Box<dyn Backend> {
+    match kind {
+        BackendKind::Cpu => Box::new(Cpu),
+        BackendKind::Gpu => Box::new(Gpu),
+    }
+}
+
+/// Uploads `input` to the GPU, rasterizes the output triangles with the input
+/// coordinates as UVs, and reads the framebuffer back as an image.
+///
+/// Each output vertex is placed in normalized device coordinates and tagged
+/// with its input UV, so the hardware interpolates the mapping perspective-
+/// correctly and samples the uploaded texture for free.
+async fn render_gpu(
+    input: &Image,
+    warps: &[WarpTriangle],
+    width: u32,
+    height: u32,
+    interpolation: Interpolation,
+) -> Image {
+    use wgpu::util::DeviceExt;
+
+    let instance = wgpu::Instance::default();
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .await
+        .expect("no suitable GPU adapter");
+    let (device, queue) = adapter
+        .request_device(&wgpu::DeviceDescriptor::default(), None)
+        .await
+        .expect("failed to create GPU device");
+
+    // Upload the input texture.
+    let (in_width, in_height) = input.dimensions();
+    let rgba = image::DynamicImage::ImageRgb8(input.clone()).to_rgba8();
+    let texture = device.create_texture_with_data(
+        &queue,
+        &wgpu::TextureDescriptor {
+            label: Some("input texture"),
+            size: wgpu::Extent3d {
+                width: in_width,
+                height: in_height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // Linear (non-sRGB) so the sampler returns raw u8 values, matching
+            // how the CPU backend reads texels.
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        &rgba,
+    );
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let filter = match interpolation {
+        Interpolation::Nearest => wgpu::FilterMode::Nearest,
+        Interpolation::Bilinear => wgpu::FilterMode::Linear,
+    };
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        mag_filter: filter,
+        min_filter: filter,
+        ..Default::default()
+    });
+
+    // Vertex buffer: output position in NDC plus input UV per triangle vertex.
+    let mut vertices: Vec<f32> = Vec::with_capacity(warps.len() * 3 * 4);
+    for warp in warps {
+        for (output, input_uv) in [
+            (warp.output.0, warp.input.0),
+            (warp.output.1, warp.input.1),
+            (warp.output.2, warp.input.2),
+        ] {
+            vertices.push((output.x as f32 / width as f32) * 2.0 - 1.0);
+            vertices.push(1.0 - (output.y as f32 / height as f32) * 2.0);
+            vertices.push(input_uv.x as f32 / in_width as f32);
+            vertices.push(input_uv.y as f32 / in_height as f32);
+        }
+    }
+    let vertex_count = (vertices.len() / 4) as u32;
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("vertices"),
+        contents: bytemuck::cast_slice(&vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("warp shader"),
+        source: wgpu::ShaderSource::Wgsl(WARP_SHADER.into()),
+    });
+
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("texture bind group layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("texture bind group"),
+        layout: &bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+        ],
+    });
+
+    // Match the input's linear format so no sRGB encode happens on write-out.
+    let target_format = wgpu::TextureFormat::Rgba8Unorm;
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("warp pipeline layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("warp pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: 4 * std::mem::size_of::<f32>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2],
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(target_format.into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    // Offscreen render target.
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("output texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: target_format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+    // Readback buffer with rows padded to the required alignment.
+    let unpadded = width * 4;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded = unpadded.div_ceil(align) * align;
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size: (padded * height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("warp pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        pass.draw(0..vertex_count, 0..1);
+    }
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture: &target,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &readback,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded),
+                rows_per_image: Some(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit([encoder.finish()]);
+
+    // Map the readback buffer and strip the per-row padding.
+    let slice = readback.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+    let data = slice.get_mapped_range();
+
+    let mut output = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let offset = (y * padded + x * 4) as usize;
+            output.put_pixel(
+                x,
+                y,
+                Rgb {
+                    0: [data[offset], data[offset + 1], data[offset + 2]],
+                },
+            );
+        }
+    }
+
+    output
+}
+
+/// WGSL program: pass output NDC positions through and sample the input
+/// texture at the interpolated UVs.
+const WARP_SHADER: &str = r#"
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec2<f32>, @location(1) uv: vec2<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = vec4<f32>(position, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@group(0) @binding(0) var input_texture: texture_2d<f32>;
+@group(0) @binding(1) var input_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(input_texture, input_sampler, in.uv);
+}
+"#;
+
+/// Warps `input` through a single projective transform.
+///
+/// `inverse` maps an output coordinate back to the input texture; the result is
+/// divided by its homogeneous `w` component before sampling so perspective is
+/// handled correctly.
+fn warp_projective(
+    input: &Image,
+    inverse: &Matrix3<f64>,
+    width: u32,
+    height: u32,
+    interpolation: Interpolation,
+) -> Image {
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let source = inverse * Vector3::new(x as f64, y as f64, 1.0);
+        sample(input, source.x / source.z, source.y / source.z, interpolation)
+    })
+}
+
+/// Tests whether `point` falls inside `triangle` via barycentric coordinates.
+fn in_triangle(triangle: &Triangle, point: Vector2<f64>) -> bool {
+    let (a, b, c) = triangle;
+    let v0 = b - a;
+    let v1 = c - a;
+    let v2 = point - a;
+
+    let denominator = v0.x * v1.y - v1.x * v0.y;
+    if denominator.abs() < f64::EPSILON {
+        return false;
+    }
+
+    let v = (v2.x * v1.y - v1.x * v2.y) / denominator;
+    let w = (v0.x * v2.y - v2.x * v0.y) / denominator;
+    let u = 1.0 - v - w;
+
+    u >= 0.0 && v >= 0.0 && w >= 0.0
+}
+
+/// Triangulates `points` with the Bowyer–Watson incremental algorithm.
+///
+/// The returned triples index into `points`. A super-triangle large enough to
+/// enclose every point bootstraps the mesh; each point is inserted by deleting
+/// the triangles whose circumcircle contains it and re-filling the resulting
+/// cavity, and any triangle still touching the super-triangle is dropped at the
+/// end.
+fn triangulate(points: &[Vector2<f64>]) -> Vec<[usize; 3]> {
+    if points.len() < 3 {
+        return Vec::new();
+    }
+
+    // Super-triangle vertices are appended past the real points.
+    let mut vertices = points.to_vec();
+    let (min_x, min_y, max_x, max_y) = points.iter().fold(
+        (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+        |(min_x, min_y, max_x, max_y), p| {
+            (min_x.min(p.x), min_y.min(p.y), max_x.max(p.x), max_y.max(p.y))
+        },
+    );
+    let delta = (max_x - min_x).max(max_y - min_y).max(1.0);
+    let mid_x = (min_x + max_x) / 2.0;
+    let mid_y = (min_y + max_y) / 2.0;
+
+    let s0 = vertices.len();
+    vertices.push(Vector2::new(mid_x - 20.0 * delta, mid_y - delta));
+    vertices.push(Vector2::new(mid_x, mid_y + 20.0 * delta));
+    vertices.push(Vector2::new(mid_x + 20.0 * delta, mid_y - delta));
+
+    let mut triangles = vec![[s0, s0 + 1, s0 + 2]];
+
+    for p in 0..points.len() {
+        let point = vertices[p];
+
+        // Collect the triangles whose circumcircle contains the new point.
+        let bad: Vec<[usize; 3]> = triangles
+            .iter()
+            .copied()
+            .filter(|&[a, b, c]| {
+                circumcircle_contains(vertices[a], vertices[b], vertices[c], point)
+            })
+            .collect();
+
+        // Boundary of the cavity: edges belonging to exactly one bad triangle.
+        let mut boundary: Vec<(usize, usize)> = Vec::new();
+        for &[a, b, c] in &bad {
+            for edge in [(a, b), (b, c), (c, a)] {
+                if let Some(index) = boundary.iter().position(|&e| same_edge(e, edge)) {
+                    boundary.swap_remove(index);
+                } else {
+                    boundary.push(edge);
+                }
+            }
+        }
+
+        triangles.retain(|t| !bad.iter().any(|b| b == t));
+
+        for (a, b) in boundary {
+            triangles.push([a, b, p]);
+        }
+    }
+
+    // Drop anything still connected to the super-triangle.
+    triangles.retain(|&[a, b, c]| a < s0 && b < s0 && c < s0);
+    triangles
+}
+
+/// Whether two undirected edges share the same endpoints.
+fn same_edge(a: (usize, usize), b: (usize, usize)) -> bool {
+    a == b || a == (b.1, b.0)
+}
+
+/// Whether `p` lies inside the circumcircle of triangle `(a, b, c)`.
+fn circumcircle_contains(
+    a: Vector2<f64>,
+    b: Vector2<f64>,
+    c: Vector2<f64>,
+    p: Vector2<f64>,
+) -> bool {
+    let d = 2.0 * (a.x * (b.y - c.y) + b.x * (c.y - a.y) + c.x * (a.y - b.y));
+    if d.abs() < f64::EPSILON {
+        return false;
+    }
+
+    let a2 = a.x * a.x + a.y * a.y;
+    let b2 = b.x * b.x + b.y * b.y;
+    let c2 = c.x * c.x + c.y * c.y;
+
+    let center = Vector2::new(
+        (a2 * (b.y - c.y) + b2 * (c.y - a.y) + c2 * (a.y - b.y)) / d,
+        (a2 * (c.x - b.x) + b2 * (a.x - c.x) + c2 * (b.x - a.x)) / d,
+    );
+
+    (p - center).norm_squared() <= (a - center).norm_squared()
+}
+
+/// Samples `image` at the fractional coordinate `(u, v)`.
+fn sample(image: &Image, u: f64, v: f64, interpolation: Interpolation) -> Rgb<u8> {
+    match interpolation {
+        Interpolation::Nearest => clamped_texel(image, u.round() as i64, v.round() as i64),
+        Interpolation::Bilinear => sample_bilinear(image, u, v),
+    }
+}
+
+/// Reads a single texel, clamping out-of-bounds coordinates to the edge.
+fn clamped_texel(image: &Image, x: i64, y: i64) -> Rgb<u8> {
     let (width, height) = image.dimensions();
-    let ignore_color = Rgb { 0: [0; 3] };
+    let x = x.clamp(0, width as i64 - 1) as u32;
+    let y = y.clamp(0, height as i64 - 1) as u32;
+    *image.get_pixel(x, y)
+}
+
+/// Blends the four texels surrounding `(u, v)` by the fractional parts.
+fn sample_bilinear(image: &Image, u: f64, v: f64) -> Rgb<u8> {
+    let x = u.floor() as i64;
+    let y = v.floor() as i64;
+    let fu = u - x as f64;
+    let fv = v - y as f64;
+
+    let p00 = clamped_texel(image, x, y);
+    let p10 = clamped_texel(image, x + 1, y);
+    let p01 = clamped_texel(image, x, y + 1);
+    let p11 = clamped_texel(image, x + 1, y + 1);
+
+    let mut pixel = Rgb { 0: [0; 3] };
+    for c in 0..3 {
+        let blended = (1.0 - fu) * (1.0 - fv) * p00.0[c] as f64
+            + fu * (1.0 - fv) * p10.0[c] as f64
+            + (1.0 - fu) * fv * p01.0[c] as f64
+            + fu * fv * p11.0[c] as f64;
+        pixel.0[c] = blended.round().clamp(0.0, 255.0) as u8;
+    }
+
+    pixel
+}
+
+/// Builds a normalized 1D Gaussian kernel covering three standard deviations.
+fn gaussian_kernel(sigma: f64) -> Vec<f64> {
+    let radius = (3.0 * sigma).ceil() as i64;
+    let mut kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
 
-    struct Marker<'a> {
-        pixel: &'a Rgb<u8>,
-        position: Position,
+    let sum: f64 = kernel.iter().sum();
+    for weight in &mut kernel {
+        *weight /= sum;
     }
 
-    // Find all markers
-    let mut markers = Vec::new();
+    kernel
+}
+
+/// Blurs `image` with a separable Gaussian, convolving rows then columns.
+fn gaussian_blur(image: &Image, sigma: f64) -> Image {
+    let kernel = gaussian_kernel(sigma);
+    let radius = (kernel.len() / 2) as i64;
+    let horizontal = convolve_axis(image, &kernel, radius, true);
+    convolve_axis(&horizontal, &kernel, radius, false)
+}
+
+/// Convolves a 1D `kernel` along one axis with edge clamping.
+fn convolve_axis(image: &Image, kernel: &[f64], radius: i64, horizontal: bool) -> Image {
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let mut channels = [0.0; 3];
+        for (k, weight) in kernel.iter().enumerate() {
+            let offset = k as i64 - radius;
+            let (sx, sy) = if horizontal {
+                (x as i64 + offset, y as i64)
+            } else {
+                (x as i64, y as i64 + offset)
+            };
+            let texel = clamped_texel(image, sx, sy);
+            for c in 0..3 {
+                channels[c] += weight * texel.0[c] as f64;
+            }
+        }
+
+        let mut pixel = Rgb { 0: [0; 3] };
+        for c in 0..3 {
+            pixel.0[c] = channels[c].round().clamp(0.0, 255.0) as u8;
+        }
+        pixel
+    })
+}
+
+/// Applies a general NxN convolution matrix per channel with edge clamping.
+///
+/// Weights are row-major; each channel is divided by `divisor` and offset by
+/// `bias` before clamping back to `u8`.
+fn convolve(image: &Image, kernel: &[f64], divisor: f64, bias: f64) -> Image {
+    let size = (kernel.len() as f64).sqrt() as i64;
+    let radius = size / 2;
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let mut channels = [0.0; 3];
+        for ky in 0..size {
+            for kx in 0..size {
+                let weight = kernel[(ky * size + kx) as usize];
+                let texel =
+                    clamped_texel(image, x as i64 + kx - radius, y as i64 + ky - radius);
+                for c in 0..3 {
+                    channels[c] += weight * texel.0[c] as f64;
+                }
+            }
+        }
+
+        let mut pixel = Rgb { 0: [0; 3] };
+        for c in 0..3 {
+            pixel.0[c] = (channels[c] / divisor + bias).round().clamp(0.0, 255.0) as u8;
+        }
+        pixel
+    })
+}
+
+/// Returns the position of each marker dot.
+///
+/// A dot is painted with a single color but covers many pixels, so pixels are
+/// clustered by their exact color and each cluster collapses to its centroid —
+/// one `Position` per dot. The resulting markers are ordered by color
+/// precedence, which considers the RGB colors as a weight BGR where R is least
+/// significant and B most significant (see `get_precedence`). Both images share
+/// that ordering, so index `i` refers to the same logical marker in either one.
+fn find_markers(image: Image) -> Vec<Position> {
+    let (width, height) = image.dimensions();
+    let ignore_color = Rgb { 0: [0; 3] };
+
+    // Accumulate the pixel count and summed coordinates per distinct color.
+    let mut clusters: HashMap<[u8; 3], (u64, u64, u64)> = HashMap::new();
 
     for x in 0..width {
         for y in 0..height {
             let pixel = image.get_pixel(x, y);
 
             if pixel != &ignore_color {
-                let marker = Marker {
-                    pixel,
-                    position: Position { x, y },
-                };
-
-                markers.push(marker);
+                let cluster = clusters.entry(pixel.0).or_insert((0, 0, 0));
+                cluster.0 += x as u64;
+                cluster.1 += y as u64;
+                cluster.2 += 1;
             }
         }
     }
 
-    // Put them in order
-    markers.sort_by(|a, b| {
-        let a = get_precedence(&a.pixel);
-        let b = get_precedence(&b.pixel);
-
-        a.cmp(&b)
-    });
+    // One marker per color, placed at the cluster centroid.
+    let mut markers: Vec<(u32, Position)> = clusters
+        .into_iter()
+        .map(|(color, (sum_x, sum_y, count))| {
+            let position = Position {
+                x: (sum_x / count) as u32,
+                y: (sum_y / count) as u32,
+            };
+            (get_precedence(&Rgb { 0: color }), position)
+        })
+        .collect();
 
-    // Discard color information
-    markers.into_iter().map(|marker| marker.position).collect()
+    // Put them in color-precedence order and discard color information.
+    markers.sort_by_key(|marker| marker.0);
+    markers.into_iter().map(|(_, position)| position).collect()
 }
 
 fn get_precedence(pixel: &Rgb<u8>) -> u32 {
@@ -102,94 +986,421 @@ fn get_precedence(pixel: &Rgb<u8>) -> u32 {
     r + 256 * g + 256 * 256 * b
 }
 
-fn make_triangle(a: &Position, b: &Position, c: &Position) -> Triangle {
-    let a = Vector2::new(a.x as f64, a.y as f64);
-    let b = Vector2::new(b.x as f64, b.y as f64);
-    let c = Vector2::new(c.x as f64, c.y as f64);
+/// A detected feature point and the descriptor used to match it.
+struct Feature {
+    position: Vector2<f64>,
+    descriptor: Vec<f64>,
+}
+
+/// Deterministic xorshift64* generator.
+///
+/// RANSAC needs random samples but reproducible runs matter more than true
+/// randomness, so a fixed-seed generator keeps the crate free of an external
+/// RNG dependency.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed | 1 }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
 
-    (a, b, c)
+    /// Uniform index in `0..bound`.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
 }
 
-/// Pads a 2x2 transform matrix to a 3x3 one
-fn pad_matrix(matrix: &Matrix2<f64>) -> Matrix3<f64> {
-    Matrix3::new(
-        matrix.m11, matrix.m12, 0.0, matrix.m21, matrix.m22, 0.0, 0.0, 0.0, 1.0,
-    )
+/// Converts an RGB image to a flat row-major luminance buffer.
+fn to_gray(image: &Image) -> Vec<f64> {
+    image
+        .pixels()
+        .map(|p| 0.299 * p.0[0] as f64 + 0.587 * p.0[1] as f64 + 0.114 * p.0[2] as f64)
+        .collect()
 }
 
-/// Chops the 3rd dimension off a 3d vector
-// fn chop_vector(vector: &Vector3<f64>) -> Vector2<f64> {
-//     Vector2::new(vector.x, vector.y)
-// }
+/// Shifts a descriptor to zero mean and unit norm so matching tolerates
+/// brightness and contrast changes.
+fn normalize(patch: &mut [f64]) {
+    let mean = patch.iter().sum::<f64>() / patch.len() as f64;
+    for value in patch.iter_mut() {
+        *value -= mean;
+    }
 
-/// Adds a 3rd dimension 1 to a vector
-fn pad_vector(vector: &Vector2<f64>) -> Vector3<f64> {
-    Vector3::new(vector.x, vector.y, 1.0)
+    let norm = patch.iter().map(|v| v * v).sum::<f64>().sqrt();
+    if norm > f64::EPSILON {
+        for value in patch.iter_mut() {
+            *value /= norm;
+        }
+    }
 }
 
-/// Gets the transformation matrix to go from an input triangle to output
-fn get_transform(input: Triangle, output: Triangle) -> Matrix3<f64> {
-    // Find translation matrix
-    let translation_vector = output.0 - input.0;
-    let translation_matrix = Matrix3::new_translation(&translation_vector);
+/// Detects Harris corners in `image`, describing each with a normalized
+/// grayscale patch.
+///
+/// Responses are thresholded relative to the strongest corner, kept only where
+/// locally maximal so matches spread across the texture, and capped at `MAX`.
+fn detect_features(image: &Image) -> Vec<Feature> {
+    const WINDOW: i64 = 1;
+    const PATCH: i64 = 3; // descriptor half-width, giving a 7×7 patch
+    const K: f64 = 0.04;
+    const MAX: usize = 1000;
 
-    // Find rotation matrix
-    let input_01 = input.1 - input.0;
-    let output_01 = output.1 - output.0;
+    let (width, height) = image.dimensions();
+    let (w, h) = (width as i64, height as i64);
+    let gray = to_gray(image);
+    let at = |x: i64, y: i64| {
+        let x = x.clamp(0, w - 1) as usize;
+        let y = y.clamp(0, h - 1) as usize;
+        gray[y * width as usize + x]
+    };
 
-    let input_01_angle = input_01.y.atan2(input_01.x);
-    let output_01_angle = output_01.y.atan2(output_01.x);
+    // Harris corner response per pixel.
+    let mut response = vec![0.0; gray.len()];
+    for y in 0..h {
+        for x in 0..w {
+            let (mut ixx, mut iyy, mut ixy) = (0.0, 0.0, 0.0);
+            for wy in -WINDOW..=WINDOW {
+                for wx in -WINDOW..=WINDOW {
+                    let ix = at(x + wx + 1, y + wy) - at(x + wx - 1, y + wy);
+                    let iy = at(x + wx, y + wy + 1) - at(x + wx, y + wy - 1);
+                    ixx += ix * ix;
+                    iyy += iy * iy;
+                    ixy += ix * iy;
+                }
+            }
+            let det = ixx * iyy - ixy * ixy;
+            let trace = ixx + iyy;
+            response[(y * w + x) as usize] = det - K * trace * trace;
+        }
+    }
 
-    let angle_difference = output_01_angle - input_01_angle;
-    let rotation_matrix = Matrix3::new_rotation(angle_difference);
+    let max_response = response.iter().cloned().fold(f64::MIN, f64::max);
+    let threshold = max_response * 0.01;
 
-    // Find change of basis matrix
-    let rot_270_matrix = Matrix2::new(0.0, 1.0, 1.0, 0.0);
-    let output_01_perpendicular = rot_270_matrix * output_01;
+    // Keep local maxima above the threshold.
+    let mut corners: Vec<(i64, i64, f64)> = Vec::new();
+    for y in 0..h {
+        for x in 0..w {
+            let r = response[(y * w + x) as usize];
+            if r <= threshold {
+                continue;
+            }
+            let mut maximal = true;
+            for ny in -WINDOW..=WINDOW {
+                for nx in -WINDOW..=WINDOW {
+                    let sx = (x + nx).clamp(0, w - 1);
+                    let sy = (y + ny).clamp(0, h - 1);
+                    if response[(sy * w + sx) as usize] > r {
+                        maximal = false;
+                    }
+                }
+            }
+            if maximal {
+                corners.push((x, y, r));
+            }
+        }
+    }
+
+    corners.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+    corners.truncate(MAX);
+
+    corners
+        .into_iter()
+        .map(|(x, y, _)| {
+            let mut patch = Vec::new();
+            for py in -PATCH..=PATCH {
+                for px in -PATCH..=PATCH {
+                    patch.push(at(x + px, y + py));
+                }
+            }
+            normalize(&mut patch);
+
+            Feature {
+                position: Vector2::new(x as f64, y as f64),
+                descriptor: patch,
+            }
+        })
+        .collect()
+}
+
+/// Euclidean distance between two feature descriptors.
+fn descriptor_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Forms tentative correspondences with Lowe's nearest/second-nearest ratio
+/// test, keeping only matches that are distinctly closer than the runner-up.
+fn match_features(a: &[Feature], b: &[Feature]) -> Vec<(usize, usize)> {
+    const RATIO: f64 = 0.8;
+    let mut matches = Vec::new();
+
+    for (i, fa) in a.iter().enumerate() {
+        let (mut best, mut second, mut best_j) = (f64::MAX, f64::MAX, 0);
+        for (j, fb) in b.iter().enumerate() {
+            let distance = descriptor_distance(&fa.descriptor, &fb.descriptor);
+            if distance < best {
+                second = best;
+                best = distance;
+                best_j = j;
+            } else if distance < second {
+                second = distance;
+            }
+        }
+        if best < RATIO * second {
+            matches.push((i, best_j));
+        }
+    }
+
+    matches
+}
+
+/// Robustly estimates the `input → output` transform from tentative matches.
+///
+/// Each iteration fits a candidate from a minimal random sample — three matches
+/// for affine, four for homography — and counts inliers whose reprojection
+/// error stays under `threshold` pixels. The largest consensus set wins and the
+/// final transform is refit over all of its inliers.
+fn ransac(
+    input: &[Vector2<f64>],
+    output: &[Vector2<f64>],
+    mapping: Mapping,
+    threshold: f64,
+    iterations: usize,
+) -> Result<Matrix3<f64>> {
+    let sample_size = match mapping {
+        Mapping::Affine => 3,
+        Mapping::Homography => 4,
+    };
+    ensure!(
+        input.len() >= sample_size,
+        "need at least {sample_size} matches to fit a {mapping:?} transform, found {}",
+        input.len()
+    );
+    let fit = |input: &[Vector2<f64>], output: &[Vector2<f64>]| match mapping {
+        Mapping::Affine => fit_affine(input, output),
+        Mapping::Homography => fit_homography(input, output),
+    };
+
+    let mut rng = Rng::new(0x9E37_79B9_7F4A_7C15);
+    let mut best: Vec<usize> = Vec::new();
+
+    for _ in 0..iterations {
+        // Draw a minimal set of distinct matches.
+        let mut sample = Vec::with_capacity(sample_size);
+        while sample.len() < sample_size {
+            let index = rng.below(input.len());
+            if !sample.contains(&index) {
+                sample.push(index);
+            }
+        }
+
+        let sample_in: Vec<_> = sample.iter().map(|&i| input[i]).collect();
+        let sample_out: Vec<_> = sample.iter().map(|&i| output[i]).collect();
+        let candidate = fit(&sample_in, &sample_out);
 
-    let change_basis_matrix_2d = Matrix2::new(
-        output_01.x,
-        output_01_perpendicular.x,
-        output_01.y,
-        output_01_perpendicular.y,
-    )
-    .try_inverse()
-    .unwrap();
+        let inliers: Vec<usize> = (0..input.len())
+            .filter(|&i| (project(&candidate, input[i]) - output[i]).norm() < threshold)
+            .collect();
 
-    let unchange_basis_matrix = pad_matrix(&Matrix2::new(
-        output_01.x,
-        output_01_perpendicular.x,
-        output_01.y,
-        output_01_perpendicular.y,
-    ));
+        if inliers.len() > best.len() {
+            best = inliers;
+        }
+    }
 
-    let change_basis_matrix = pad_matrix(&change_basis_matrix_2d);
+    // No consensus set means no usable model — fitting over zero inliers would
+    // panic. This happens when `--ransac-iters` is 0.
+    ensure!(
+        !best.is_empty(),
+        "RANSAC found no model; try more --ransac-iters or a larger --ransac-threshold"
+    );
 
-    // Change triangle bases
-    let m = change_basis_matrix * rotation_matrix * translation_matrix;
+    let input_inliers: Vec<_> = best.iter().map(|&i| input[i]).collect();
+    let output_inliers: Vec<_> = best.iter().map(|&i| output[i]).collect();
+    Ok(fit(&input_inliers, &output_inliers))
+}
 
-    let input_1_in_01 = m * pad_vector(&input.1);
-    let input_2_in_01 = m * pad_vector(&input.2);
-    let output_1_in_01 = m * pad_vector(&output.1);
-    let output_2_in_01 = m * pad_vector(&output.2);
+/// Fits a projective homography mapping `input` points onto `output` points.
+///
+/// Solves the Direct Linear Transform: each correspondence contributes two rows
+/// to a `2n×9` matrix, and the homography is its right null vector, normalized
+/// so the bottom-right entry is 1. Unlike an affine fit this represents full
+/// perspective, not just translation/rotation/scale/shear.
+fn fit_homography(input: &[Vector2<f64>], output: &[Vector2<f64>]) -> Matrix3<f64> {
+    let n = input.len();
+    let mut a = DMatrix::zeros(2 * n, 9);
 
-    // Find scale/shear matrix
-    let scale_01 = output_1_in_01.x / input_1_in_01.x;
-    let scale_01_perpendicular = output_2_in_01.y / input_2_in_01.y;
-    let shear_01 = (output_2_in_01.x - input_2_in_01.x * scale_01) / output_2_in_01.y;
+    for i in 0..n {
+        let (x, y) = (input[i].x, input[i].y);
+        let (xp, yp) = (output[i].x, output[i].y);
 
-    let scale_matrix_2d = Matrix2::new(scale_01, shear_01, 0.0, scale_01_perpendicular);
-    let scale_matrix = pad_matrix(&scale_matrix_2d);
+        a.row_mut(2 * i)
+            .copy_from_slice(&[-x, -y, -1.0, 0.0, 0.0, 0.0, x * xp, y * xp, xp]);
+        a.row_mut(2 * i + 1)
+            .copy_from_slice(&[0.0, 0.0, 0.0, -x, -y, -1.0, x * yp, y * yp, yp]);
+    }
 
-    // Print out
-    println!("Translation matrix: {translation_matrix:?}");
-    println!("Rotation matrix: {rotation_matrix:?}");
-    println!("Change of basis matrix: {change_basis_matrix:?}");
-    println!("Scale/shear matrix {scale_matrix:?}");
+    // The homography is the right null vector of `A`, i.e. the eigenvector of
+    // `AᵀA` for its smallest eigenvalue. Working through the `9×9` `AᵀA` avoids
+    // the reduced-SVD trap where a thin `A` (8 rows for four points) never
+    // yields the full null space.
+    let ata = a.transpose() * a;
+    let eigen = ata.symmetric_eigen();
+    let smallest = eigen
+        .eigenvalues
+        .iter()
+        .enumerate()
+        .min_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+        .map(|(index, _)| index)
+        .unwrap();
+    let h = eigen.eigenvectors.column(smallest);
 
-    unchange_basis_matrix
-        * scale_matrix
-        * change_basis_matrix
-        * rotation_matrix
-        * translation_matrix
+    let matrix = Matrix3::new(
+        h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], h[8],
+    );
+    matrix / h[8]
+}
+
+/// Least-squares affine fit of `input → output`, needing at least three points.
+///
+/// Solves the over-determined system `x' = a·x + b·y + c`, `y' = d·x + e·y + f`
+/// and packs the coefficients into a `3×3` matrix whose bottom row is `[0 0 1]`.
+fn fit_affine(input: &[Vector2<f64>], output: &[Vector2<f64>]) -> Matrix3<f64> {
+    let n = input.len();
+    let mut m = DMatrix::zeros(2 * n, 6);
+    let mut rhs = DVector::zeros(2 * n);
+
+    for i in 0..n {
+        let (x, y) = (input[i].x, input[i].y);
+        m.row_mut(2 * i).copy_from_slice(&[x, y, 1.0, 0.0, 0.0, 0.0]);
+        m.row_mut(2 * i + 1)
+            .copy_from_slice(&[0.0, 0.0, 0.0, x, y, 1.0]);
+        rhs[2 * i] = output[i].x;
+        rhs[2 * i + 1] = output[i].y;
+    }
+
+    let p = SVD::new(m, true, true).solve(&rhs, 1e-12).unwrap();
+    Matrix3::new(p[0], p[1], p[2], p[3], p[4], p[5], 0.0, 0.0, 1.0)
+}
+
+/// Applies a projective transform to a point, dividing by the homogeneous `w`.
+fn project(matrix: &Matrix3<f64>, point: Vector2<f64>) -> Vector2<f64> {
+    let v = matrix * Vector3::new(point.x, point.y, 1.0);
+    Vector2::new(v.x / v.z, v.y / v.z)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points are considered equal within half a pixel.
+    fn close(a: Vector2<f64>, b: Vector2<f64>) -> bool {
+        (a - b).norm() < 0.5
+    }
+
+    #[test]
+    fn fit_affine_recovers_a_translation() {
+        // Three corners of a square shifted by (8, 6): the fit must reproduce
+        // the shift exactly, including for the un-sampled fourth corner.
+        let input = [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(10.0, 0.0),
+            Vector2::new(0.0, 10.0),
+        ];
+        let output = [
+            Vector2::new(8.0, 6.0),
+            Vector2::new(18.0, 6.0),
+            Vector2::new(8.0, 16.0),
+        ];
+
+        let matrix = fit_affine(&input, &output);
+        for (i, o) in input.iter().zip(&output) {
+            assert!(close(project(&matrix, *i), *o));
+        }
+        assert!(close(
+            project(&matrix, Vector2::new(10.0, 10.0)),
+            Vector2::new(18.0, 16.0)
+        ));
+    }
+
+    #[test]
+    fn fit_affine_recovers_rotate_scale_translate() {
+        let transform = Matrix3::new(0.0, -2.0, 5.0, 2.0, 0.0, -3.0, 0.0, 0.0, 1.0);
+        let input = [
+            Vector2::new(1.0, 2.0),
+            Vector2::new(4.0, 1.0),
+            Vector2::new(2.0, 5.0),
+        ];
+        let output: Vec<_> = input.iter().map(|p| project(&transform, *p)).collect();
+
+        let matrix = fit_affine(&input, &output);
+        for (i, o) in input.iter().zip(&output) {
+            assert!(close(project(&matrix, *i), *o));
+        }
+    }
+
+    #[test]
+    fn fit_homography_recovers_the_four_corner_map() {
+        let input = [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(100.0, 0.0),
+            Vector2::new(100.0, 100.0),
+            Vector2::new(0.0, 100.0),
+        ];
+        // A genuinely projective target quad (not an affine parallelogram).
+        let output = [
+            Vector2::new(10.0, 20.0),
+            Vector2::new(90.0, 5.0),
+            Vector2::new(80.0, 95.0),
+            Vector2::new(5.0, 70.0),
+        ];
+
+        let matrix = fit_homography(&input, &output);
+        for (i, o) in input.iter().zip(&output) {
+            assert!(close(project(&matrix, *i), *o));
+        }
+    }
+
+    #[test]
+    fn triangulate_splits_a_square_into_two_triangles() {
+        let points = [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(10.0, 0.0),
+            Vector2::new(10.0, 10.0),
+            Vector2::new(0.0, 10.0),
+        ];
+        let triangles = triangulate(&points);
+
+        assert_eq!(triangles.len(), 2);
+        for triangle in &triangles {
+            assert!(triangle.iter().all(|&v| v < points.len()));
+        }
+    }
+
+    #[test]
+    fn sample_bilinear_blends_the_four_texels() {
+        // A 2×2 image with one bright corner; the centre averages all four.
+        let image = Image::from_fn(2, 2, |x, y| {
+            let value = if x == 0 && y == 0 { 200 } else { 0 };
+            Rgb { 0: [value, value, value] }
+        });
+
+        let centre = sample_bilinear(&image, 0.5, 0.5);
+        assert_eq!(centre.0[0], 50);
+    }
 }